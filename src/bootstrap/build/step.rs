@@ -8,7 +8,18 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::collections::HashSet;
+use std::any::Any;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Condvar, Mutex};
+
+use crossbeam;
 
 use build::{Build, Compiler};
 
@@ -90,27 +101,83 @@ macro_rules! define_source {
 
 targets!(define_source);
 
+#[derive(PartialEq)]
+enum Color {
+    // Currently being visited (on the DFS stack); seeing this again means
+    // we've found a cycle.
+    Gray,
+    // Fully visited; all of its deps are already in `ret`.
+    Black,
+}
+
 pub fn all(build: &Build) -> Vec<Step> {
+    toposort(top_level(build), |step| step.deps(build))
+}
+
+/// Topologically sorts `roots` and everything reachable from them via
+/// `deps`, in dependency-first order (every step appears only after
+/// everything it depends on).
+///
+/// Cycles are detected with a three-color DFS: nodes are implicitly
+/// white (unvisited) until they get a `Color` entry, turn gray on entry
+/// and black on exit, and re-encountering a gray node means `deps` has a
+/// cycle, which would otherwise manifest as unbounded recursion and a
+/// stack overflow. On a cycle this panics with the full back-edge path,
+/// from the cycle's root back to the step that closes the loop.
+///
+/// Pulled out of `all()` (which supplies `deps` as `Step::deps` bound to
+/// a real `Build`) so the graph algorithm itself can be unit tested
+/// against a synthetic dependency set.
+fn toposort<'a, D>(roots: Vec<Step<'a>>, deps: D) -> Vec<Step<'a>>
+    where D: Fn(&Step<'a>) -> Vec<Step<'a>>
+{
     let mut ret = Vec::new();
-    let mut all = HashSet::new();
-    for target in top_level(build) {
-        fill(build, &target, &mut ret, &mut all);
+    let mut colors = HashMap::new();
+    let mut path = Vec::new();
+    for target in roots {
+        fill(&target, &deps, &mut ret, &mut colors, &mut path);
     }
     return ret;
 
-    fn fill<'a>(build: &'a Build,
-                target: &Step<'a>,
-                ret: &mut Vec<Step<'a>>,
-                set: &mut HashSet<Step<'a>>) {
-        if set.insert(target.clone()) {
-            for dep in target.deps(build) {
-                fill(build, &dep, ret, set);
+    fn fill<'a, D>(target: &Step<'a>,
+                   deps: &D,
+                   ret: &mut Vec<Step<'a>>,
+                   colors: &mut HashMap<Step<'a>, Color>,
+                   path: &mut Vec<Step<'a>>)
+        where D: Fn(&Step<'a>) -> Vec<Step<'a>>
+    {
+        match colors.get(target) {
+            Some(&Color::Gray) => {
+                path.push(target.clone());
+                let start = path.iter().position(|s| s == target).unwrap();
+                panic!("dependency cycle detected in step graph:\n{}",
+                       cycle_diagnostic(&path[start..]));
             }
-            ret.push(target.clone());
+            Some(&Color::Black) => return,
+            None => {}
         }
+
+        colors.insert(target.clone(), Color::Gray);
+        path.push(target.clone());
+        for dep in deps(target) {
+            fill(&dep, deps, ret, colors, path);
+        }
+        path.pop();
+        colors.insert(target.clone(), Color::Black);
+        ret.push(target.clone());
     }
 }
 
+/// Formats a cycle as the chain of `Source` variants and targets from the
+/// cycle's root back to the step that closes the loop, e.g.
+/// `Libstd { .. } (x86_64) -> Rustc { .. } (x86_64) -> Libstd { .. } (x86_64)`.
+fn cycle_diagnostic(cycle: &[Step]) -> String {
+    cycle.iter()
+         .map(|step| format!("{:?} ({})", step.src, step.target))
+         .collect::<Vec<_>>()
+         .join(" -> ")
+}
+
 fn top_level(build: &Build) -> Vec<Step> {
     let mut targets = Vec::new();
     let stage = build.flags.stage.unwrap_or(2);
@@ -274,9 +341,555 @@ impl<'a> Step<'a> {
             }
             Source::Check { stage, compiler: _ } => {
                 vec![]
+            }
             Source::ToolRustbook { stage } => {
                 vec![self.librustc(stage, self.compiler(stage))]
             }
         }
     }
 }
+
+impl<'a> Step<'a> {
+    /// Computes a fingerprint covering everything that feeds into this
+    /// step, so that a rerun of `bootstrap` can tell whether the step
+    /// needs to happen again.
+    ///
+    /// Two fingerprints are only expected to match if rerunning the step
+    /// would produce identical output, so every `Source` variant folds in
+    /// each dependency's own fingerprint -- that's what makes invalidation
+    /// transitive down the graph, e.g. a `Librustc` rebuild forcing a
+    /// `Libstd`/`LibstdLink` rebuild even though neither's own inputs
+    /// changed. On top of that, `Libstd`/`Librustc` also hash the state of
+    /// their source tree, and `Llvm`/`CompilerRt` their native source
+    /// checkout revision, since those are source inputs with no step of
+    /// their own to produce a fingerprint for.
+    pub fn fingerprint(&self, build: &Build) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.target.hash(&mut hasher);
+        match self.src {
+            Source::Libstd { stage, compiler } => {
+                stage.hash(&mut hasher);
+                compiler.hash(&mut hasher);
+                mtime_hash(&build.src.join("src/libstd")).hash(&mut hasher);
+            }
+            Source::Librustc { stage, compiler } => {
+                stage.hash(&mut hasher);
+                compiler.hash(&mut hasher);
+                mtime_hash(&build.src.join("src/librustc")).hash(&mut hasher);
+            }
+            Source::Llvm { .. } => {
+                git_revision(&build.src.join("src/llvm")).hash(&mut hasher);
+            }
+            Source::CompilerRt { .. } => {
+                git_revision(&build.src.join("src/compiler-rt")).hash(&mut hasher);
+            }
+            ref src => {
+                format!("{:?}", src).hash(&mut hasher);
+            }
+        }
+        for dep in self.deps(build) {
+            dep.fingerprint(build).hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Whether this step's output is actually still on disk.
+    ///
+    /// A fingerprint match alone isn't enough to trust the cache: it only
+    /// says the *inputs* look the same as last time, not that the
+    /// *output* wasn't deleted since (e.g. `rm -rf` of one stage, or a
+    /// partial `clean`, with the fingerprint file left behind).
+    pub fn output_exists(&self, build: &Build) -> bool {
+        let stage_dir = |stage: u32| {
+            build.out.join(self.target).join(format!("stage{}", stage))
+        };
+        match self.src {
+            Source::Llvm { .. } => {
+                build.out.join(self.target).join("llvm").exists()
+            }
+            Source::CompilerRt { .. } => {
+                build.out.join(self.target).join("compiler-rt").exists()
+            }
+            Source::Rustc { stage } |
+            Source::Libstd { stage, .. } |
+            Source::Librustc { stage, .. } |
+            Source::LibstdLink { stage, .. } |
+            Source::LibrustcLink { stage, .. } |
+            Source::ToolRustbook { stage } |
+            Source::Doc { stage } |
+            Source::DocBook { stage } |
+            Source::DocNomicon { stage } |
+            Source::DocStyle { stage } |
+            Source::DocStandalone { stage } |
+            Source::DocStd { stage } |
+            Source::DocRustc { stage } |
+            Source::Check { stage, .. } => stage_dir(stage).exists(),
+        }
+    }
+}
+
+/// Hashes the modification time of every file under `dir`, recursively.
+///
+/// Callers pass the specific source directory a step actually reads
+/// (e.g. `src/libstd`), not the whole checkout, so an edit somewhere
+/// unrelated (docs, a different crate) doesn't invalidate every step.
+/// This is a coarse approximation of "has the source tree changed" that
+/// avoids reading file contents; it's good enough to decide whether a
+/// step needs to rerun.
+fn mtime_hash(dir: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    visit(dir, &mut hasher);
+    return hasher.finish();
+
+    fn visit(dir: &Path, hasher: &mut DefaultHasher) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        // `read_dir`'s order isn't guaranteed stable, and `DefaultHasher`
+        // is order-sensitive, so sort by path first -- otherwise an
+        // unchanged tree can hash differently from run to run and cause
+        // spurious rebuilds.
+        let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        paths.sort();
+        for path in paths {
+            if path.is_dir() {
+                visit(&path, hasher);
+            } else if let Ok(meta) = fs::metadata(&path) {
+                if let Ok(mtime) = meta.modified() {
+                    path.hash(hasher);
+                    mtime.hash(hasher);
+                }
+            }
+        }
+    }
+}
+
+/// Hashes the current revision of the native source checkout at `dir`,
+/// falling back to hashing the path itself if `dir` isn't a git checkout.
+/// Callers pass the submodule's own directory (e.g. `src/llvm`), since
+/// that's what actually tracks its checked-out revision -- the outer
+/// monorepo's `HEAD` doesn't move when only a submodule is bumped.
+fn git_revision(dir: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let output = Command::new("git")
+                          .arg("rev-parse")
+                          .arg("HEAD")
+                          .current_dir(dir)
+                          .output();
+    match output {
+        Ok(ref out) if out.status.success() => out.stdout.hash(&mut hasher),
+        _ => dir.hash(&mut hasher),
+    }
+    hasher.finish()
+}
+
+/// Path to the file persisting the last-successful fingerprint of every
+/// step, keyed by its `Debug` representation.
+fn fingerprints_path(build: &Build) -> PathBuf {
+    build.out.join("bootstrap-fingerprints.txt")
+}
+
+/// Loads the last-successful fingerprint of each step recorded by earlier
+/// builds (including ones where some other step failed), if any. Missing
+/// or unreadable state is treated as "nothing's cached yet"
+/// rather than an error, since the very first build has no history.
+fn load_fingerprints(build: &Build) -> HashMap<String, u64> {
+    let mut contents = String::new();
+    let opened = File::open(fingerprints_path(build))
+                       .and_then(|mut f| f.read_to_string(&mut contents));
+    if opened.is_err() {
+        return HashMap::new();
+    }
+    contents.lines().filter_map(|line| {
+        let mut parts = line.splitn(2, '\t');
+        let key = match parts.next() {
+            Some(key) => key,
+            None => return None,
+        };
+        let fingerprint = match parts.next().and_then(|s| s.parse().ok()) {
+            Some(fingerprint) => fingerprint,
+            None => return None,
+        };
+        Some((key.to_string(), fingerprint))
+    }).collect()
+}
+
+/// Persists `fingerprints` so the next build can skip steps that are
+/// still up to date.
+fn save_fingerprints(build: &Build, fingerprints: &HashMap<String, u64>) {
+    let mut contents = String::new();
+    for (key, fingerprint) in fingerprints {
+        contents.push_str(key);
+        contents.push('\t');
+        contents.push_str(&fingerprint.to_string());
+        contents.push('\n');
+    }
+    let _ = File::create(fingerprints_path(build))
+                 .and_then(|mut f| f.write_all(contents.as_bytes()));
+}
+
+/// Bookkeeping shared by the worker threads in `run`: the remaining
+/// in-degree of every step, the reverse-adjacency (dependents) of every
+/// step, and the queue of steps that are currently ready to execute.
+struct Schedule<'a> {
+    in_degree: HashMap<Step<'a>, usize>,
+    dependents: HashMap<Step<'a>, Vec<Step<'a>>>,
+    ready: Vec<Step<'a>>,
+    remaining: usize,
+    // Set once some step's `run_step` call panics (the normal way a
+    // build step fails, e.g. a compile error). Once set, workers stop
+    // picking up new work rather than running steps whose dependencies
+    // never actually succeeded, and `run` re-raises the panic once every
+    // worker has exited.
+    failure: Option<(String, Box<Any + Send>)>,
+}
+
+/// Output format requested via `--dump-graph[=dot|json]`, in lieu of
+/// actually running the build.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GraphFormat {
+    Dot,
+    Json,
+}
+
+/// Single entry point the top-level driver should call in place of
+/// executing steps serially: dumps the dependency graph to stdout and
+/// returns without building anything if `build.flags.dump_graph` was
+/// set, otherwise runs the build to completion via `run`.
+pub fn build<F>(build: &Build, run_step: F)
+    where F: Fn(&Build, &Step) + Sync + Send
+{
+    match build.flags.dump_graph {
+        Some(GraphFormat::Dot) => print!("{}", dump_dot(build)),
+        Some(GraphFormat::Json) => print!("{}", dump_json(build)),
+        None => run(build, run_step),
+    }
+}
+
+/// Runs every step returned by `all(build)` to completion, dispatching
+/// steps whose dependencies have all finished to a pool of worker
+/// threads sized to `build.flags.jobs` (or the number of CPUs if that
+/// wasn't given on the command line).
+///
+/// A step only becomes eligible to run once every step that `deps()`
+/// lists for it has finished, so hard prerequisites such as
+/// `Source::Llvm` and `Source::CompilerRt` are always complete before
+/// anything that needs them starts, while unrelated steps (for example
+/// `Source::Libstd` for two different targets) run side by side.
+pub fn run<F>(build: &Build, run_step: F)
+    where F: Fn(&Build, &Step) + Sync + Send
+{
+    let steps = all(build);
+
+    let mut in_degree = HashMap::new();
+    let mut dependents: HashMap<Step, Vec<Step>> = HashMap::new();
+    for step in &steps {
+        in_degree.entry(step.clone()).or_insert(0);
+    }
+    for step in &steps {
+        for dep in step.deps(build) {
+            *in_degree.entry(step.clone()).or_insert(0) += 1;
+            dependents.entry(dep).or_insert_with(Vec::new).push(step.clone());
+        }
+    }
+
+    let ready = steps.iter()
+                     .filter(|s| in_degree[*s] == 0)
+                     .cloned()
+                     .collect();
+    let remaining = steps.len();
+    let schedule = Mutex::new(Schedule {
+        in_degree: in_degree,
+        dependents: dependents,
+        ready: ready,
+        remaining: remaining,
+        failure: None,
+    });
+    let more_work = Condvar::new();
+    let fingerprints = Mutex::new(load_fingerprints(build));
+
+    let jobs = build.flags.jobs
+                    .map(|j| j as usize)
+                    .unwrap_or_else(::num_cpus::get);
+
+    {
+        // Rebind to references before the loop: a `move` closure takes
+        // ownership of whatever it captures, so spawning from inside the
+        // loop with `&run_step` etc. directly would move `run_step`,
+        // `schedule`, `more_work` and `fingerprints` themselves on the
+        // first iteration and fail to build for `jobs > 1`.
+        let run_step = &run_step;
+        let schedule = &schedule;
+        let more_work = &more_work;
+        let fingerprints = &fingerprints;
+        crossbeam::scope(|scope| {
+            for _ in 0..jobs {
+                scope.spawn(move || {
+                    worker(build, run_step, schedule, more_work, fingerprints)
+                });
+            }
+        });
+    }
+
+    save_fingerprints(build, &fingerprints.lock().unwrap());
+
+    // A step's `run_step` panicking is the normal way a build step fails
+    // (e.g. a compile error), so re-raise it here now that every worker
+    // has exited, instead of letting it vanish along with the thread
+    // that hit it.
+    if let Some((key, payload)) = schedule.into_inner().unwrap().failure {
+        eprintln!("step {} failed", key);
+        panic::resume_unwind(payload);
+    }
+}
+
+fn worker<'a, F>(build: &'a Build,
+                  run_step: &F,
+                  schedule: &Mutex<Schedule<'a>>,
+                  more_work: &Condvar,
+                  fingerprints: &Mutex<HashMap<String, u64>>)
+    where F: Fn(&Build, &Step) + Sync + Send
+{
+    loop {
+        let step = {
+            let mut state = schedule.lock().unwrap();
+            loop {
+                if state.failure.is_some() {
+                    return;
+                }
+                if let Some(step) = state.ready.pop() {
+                    break step;
+                }
+                if state.remaining == 0 {
+                    return;
+                }
+                state = more_work.wait(state).unwrap();
+            }
+        };
+
+        // Skip the step entirely if its fingerprint matches the last
+        // successful build's and its output is still on disk; it's still
+        // marked complete below so its dependents become eligible to
+        // run. A changed upstream fingerprint (e.g. a rebuilt compiler
+        // binary) naturally changes a dependent's own fingerprint, so
+        // invalidation propagates transitively without any extra
+        // bookkeeping here.
+        let key = format!("{:?}", step);
+        let new_fingerprint = step.fingerprint(build);
+        let up_to_date = fingerprints.lock().unwrap().get(&key) == Some(&new_fingerprint) &&
+                          step.output_exists(build);
+        if !up_to_date {
+            // `run_step` panicking is how a step reports failure (e.g. a
+            // compile error), so catch it here rather than letting the
+            // unwind kill this worker without ever updating `remaining`
+            // -- otherwise every other worker parks on `more_work.wait()`
+            // forever once the shared `ready` queue runs dry.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| run_step(build, &step)));
+            match result {
+                Ok(()) => {
+                    fingerprints.lock().unwrap().insert(key, new_fingerprint);
+                }
+                Err(payload) => {
+                    let mut state = schedule.lock().unwrap();
+                    state.remaining -= 1;
+                    if state.failure.is_none() {
+                        state.failure = Some((key, payload));
+                    }
+                    drop(state);
+                    more_work.notify_all();
+                    return;
+                }
+            }
+        }
+
+        let mut state = schedule.lock().unwrap();
+        state.remaining -= 1;
+        if let Some(dependents) = state.dependents.remove(&step) {
+            for dependent in dependents {
+                let degree = state.in_degree.get_mut(&dependent).unwrap();
+                *degree -= 1;
+                if *degree == 0 {
+                    state.ready.push(dependent);
+                }
+            }
+        }
+        drop(state);
+        more_work.notify_all();
+    }
+}
+
+/// Serializes the dependency graph built by `all()` as a Graphviz `dot`
+/// file, without executing anything. Handy for running the result
+/// through `dot -Tpng` to see why a particular step is (or isn't)
+/// scheduled.
+pub fn dump_dot(build: &Build) -> String {
+    render_dot(&all(build), |step| step.deps(build))
+}
+
+/// Serializes the dependency graph built by `all()` as a JSON adjacency
+/// list, without executing anything. Each entry has the index of the
+/// step, a human-readable label, and the indices of the steps it
+/// depends on.
+pub fn dump_json(build: &Build) -> String {
+    render_json(&all(build), |step| step.deps(build))
+}
+
+/// Graphviz-`dot` rendering of `steps` and their `deps`. Split out of
+/// `dump_dot` (which supplies `deps` as `Step::deps` bound to a real
+/// `Build`) so it can be unit tested against a synthetic graph.
+fn render_dot<'a, D>(steps: &[Step<'a>], deps: D) -> String
+    where D: Fn(&Step<'a>) -> Vec<Step<'a>>
+{
+    let mut out = String::new();
+    out.push_str("digraph bootstrap {\n");
+    for (i, step) in steps.iter().enumerate() {
+        out.push_str(&format!("    n{} [label=\"{}\"];\n", i, escape(&label(step))));
+    }
+    let index = index_of(steps);
+    for (i, step) in steps.iter().enumerate() {
+        for dep in deps(step) {
+            out.push_str(&format!("    n{} -> n{};\n", i, index[&dep]));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// JSON-adjacency-list rendering of `steps` and their `deps`. Split out
+/// of `dump_json` for the same reason as `render_dot`.
+fn render_json<'a, D>(steps: &[Step<'a>], deps: D) -> String
+    where D: Fn(&Step<'a>) -> Vec<Step<'a>>
+{
+    let index = index_of(steps);
+
+    let mut out = String::new();
+    out.push_str("[\n");
+    for (i, step) in steps.iter().enumerate() {
+        let dep_ids: Vec<String> = deps(step)
+                                        .iter()
+                                        .map(|dep| index[dep].to_string())
+                                        .collect();
+        out.push_str(&format!(
+            "  {{\"id\": {}, \"label\": \"{}\", \"target\": \"{}\", \"deps\": [{}]}}",
+            i, escape(&label(step)), escape(step.target), dep_ids.join(", ")));
+        out.push_str(if i + 1 == steps.len() { "\n" } else { ",\n" });
+    }
+    out.push_str("]\n");
+    out
+}
+
+fn index_of<'a>(steps: &[Step<'a>]) -> HashMap<Step<'a>, usize> {
+    steps.iter().enumerate().map(|(i, step)| (step.clone(), i)).collect()
+}
+
+fn label(step: &Step) -> String {
+    format!("{:?} ({})", step.src, step.target)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Step, Source, toposort, cycle_diagnostic, render_dot, render_json,
+                index_of, label, escape};
+    use std::collections::HashMap;
+
+    fn step(stage: u32, target: &str) -> Step {
+        Step { src: Source::ToolRustbook { stage: stage }, target: target }
+    }
+
+    // Builds a `deps` closure for `toposort` out of a plain adjacency
+    // map, so tests don't need a real `Build`.
+    fn deps_fn<'a>(graph: HashMap<Step<'a>, Vec<Step<'a>>>)
+        -> Box<Fn(&Step<'a>) -> Vec<Step<'a>> + 'a>
+    {
+        Box::new(move |s: &Step<'a>| graph.get(s).cloned().unwrap_or_else(Vec::new))
+    }
+
+    #[test]
+    fn toposort_orders_deps_before_dependents() {
+        let a = step(0, "a");
+        let b = step(0, "b");
+        let c = step(0, "c");
+
+        let mut graph = HashMap::new();
+        graph.insert(c.clone(), vec![b.clone()]);
+        graph.insert(b.clone(), vec![a.clone()]);
+        graph.insert(a.clone(), vec![]);
+
+        let sorted = toposort(vec![c.clone()], deps_fn(graph));
+
+        assert_eq!(sorted, vec![a, b, c]);
+    }
+
+    #[test]
+    #[should_panic(expected = "dependency cycle detected")]
+    fn toposort_panics_on_cycle() {
+        let a = step(0, "a");
+        let b = step(0, "b");
+
+        let mut graph = HashMap::new();
+        graph.insert(a.clone(), vec![b.clone()]);
+        graph.insert(b.clone(), vec![a.clone()]);
+
+        toposort(vec![a], deps_fn(graph));
+    }
+
+    #[test]
+    fn cycle_diagnostic_joins_steps_with_arrows() {
+        let cycle = [step(0, "a"), step(0, "b")];
+        let msg = cycle_diagnostic(&cycle);
+        assert_eq!(msg, format!("{:?} (a) -> {:?} (b)",
+                                 Source::ToolRustbook { stage: 0 },
+                                 Source::ToolRustbook { stage: 0 }));
+    }
+
+    #[test]
+    fn render_dot_includes_nodes_and_edges() {
+        let a = step(0, "a");
+        let b = step(0, "b");
+        let steps = vec![a.clone(), b.clone()];
+
+        let mut graph = HashMap::new();
+        graph.insert(b.clone(), vec![a.clone()]);
+
+        let dot = render_dot(&steps, move |s: &Step| graph.get(s).cloned().unwrap_or_else(Vec::new));
+
+        assert!(dot.starts_with("digraph bootstrap {\n"));
+        assert!(dot.contains("n0 [label=\""));
+        assert!(dot.contains("n1 -> n0;"));
+    }
+
+    #[test]
+    fn render_json_escapes_target() {
+        let a = step(0, "a \"quoted\"");
+        let steps = vec![a];
+
+        let json = render_json(&steps, |_: &Step| vec![]);
+
+        assert!(json.contains("\\\"quoted\\\""));
+        assert!(json.contains("\"deps\": []"));
+    }
+
+    #[test]
+    fn index_of_maps_each_step_to_its_position() {
+        let steps = vec![step(0, "a"), step(0, "b")];
+        let index = index_of(&steps);
+        assert_eq!(index[&steps[0]], 0);
+        assert_eq!(index[&steps[1]], 1);
+    }
+
+    #[test]
+    fn label_formats_source_and_target() {
+        let s = step(1, "x86_64");
+        assert_eq!(label(&s), format!("{:?} (x86_64)", Source::ToolRustbook { stage: 1 }));
+    }
+
+    #[test]
+    fn escape_handles_backslashes_and_quotes() {
+        assert_eq!(escape("a\\b\"c"), "a\\\\b\\\"c");
+    }
+}